@@ -0,0 +1,325 @@
+//! Perceptual-hash based near-duplicate detection.
+//!
+//! This module implements the "difference hash" (dHash) algorithm: an image
+//! is downscaled to a small, fixed grid and every pixel is compared against
+//! its right-hand neighbour, producing a hash that is robust to resizing,
+//! re-compression and small colour shifts. Hashes that differ in only a few
+//! bits (their Hamming distance) almost always come from visually similar
+//! photos, which lets us flag near-duplicates that a simple filename match
+//! would never catch.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Width/height of the grid the image is downscaled to before hashing.
+/// One extra column (9 instead of 8) is needed to compare every column
+/// against its right neighbour.
+const HASH_WIDTH: u32 = 9;
+const HASH_HEIGHT: u32 = 8;
+
+/// Default Hamming distance below which two photos are considered
+/// near-duplicates.
+pub const DEFAULT_DUP_THRESHOLD: u32 = 10;
+
+/// Compute the 64-bit dHash of the image at `path`.
+///
+/// The image is decoded, downscaled to a `9x8` greyscale grid, and for each
+/// row the 8 adjacent-pixel comparisons are packed into 8 bits of the
+/// resulting hash (bit set when the left pixel is brighter than the right
+/// one).
+///
+/// RAW files have no directly decodable pixel grid, so their embedded JPEG
+/// preview (see [`embedded_preview`]) is hashed instead when one is present.
+pub fn dhash(path: &Path) -> io::Result<u64> {
+    let img = decode_for_hashing(path)?;
+    Ok(dhash_image(&img))
+}
+
+/// Decode `path` into an image suitable for hashing, falling back to a RAW
+/// file's embedded JPEG preview when the file itself cannot be decoded
+/// directly.
+fn decode_for_hashing(path: &Path) -> io::Result<DynamicImage> {
+    if let Ok(img) = image::open(path) {
+        return Ok(img);
+    }
+
+    embedded_preview(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no decodable image or preview"))
+}
+
+/// Extract and decode the embedded JPEG preview of a RAW file, if present.
+fn embedded_preview(path: &Path) -> Option<DynamicImage> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut bufreader = io::BufReader::new(file);
+    let exif = exif::Reader::new()
+        .read_from_container(&mut bufreader)
+        .ok()?;
+
+    let offset = exif
+        .get_field(exif::Tag::JPEGInterchangeFormat, exif::In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    let length = exif
+        .get_field(exif::Tag::JPEGInterchangeFormatLength, exif::In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+
+    let bytes = std::fs::read(path).ok()?;
+    let preview = bytes.get(offset..offset + length)?;
+    image::load_from_memory(preview).ok()
+}
+
+fn dhash_image(img: &DynamicImage) -> u64 {
+    let small = img
+        .resize_exact(HASH_WIDTH, HASH_HEIGHT, FilterType::Triangle)
+        .to_luma8();
+
+    let mut hash: u64 = 0;
+    let mut bit = 0;
+    for y in 0..HASH_HEIGHT {
+        for x in 0..HASH_WIDTH - 1 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            if left > right {
+                hash |= 1 << bit;
+            }
+            bit += 1;
+        }
+    }
+    hash
+}
+
+/// Number of differing bits between two hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// A BK-tree indexing photo hashes keyed by Hamming distance, so that
+/// "find every hash within threshold T of this one" queries stay sub-linear
+/// even for large libraries.
+#[derive(Debug, Default)]
+pub struct BkTree {
+    root: Option<Box<BkNode>>,
+}
+
+#[derive(Debug)]
+struct BkNode {
+    key: String,
+    hash: u64,
+    children: HashMap<u32, Box<BkNode>>,
+}
+
+impl BkTree {
+    pub fn new() -> Self {
+        BkTree::default()
+    }
+
+    /// Index `hash` under `key` (typically the photo's data base key).
+    pub fn insert(&mut self, key: String, hash: u64) {
+        match &mut self.root {
+            None => {
+                self.root = Some(Box::new(BkNode {
+                    key,
+                    hash,
+                    children: HashMap::new(),
+                }))
+            }
+            Some(root) => Self::insert_node(root, key, hash),
+        }
+    }
+
+    fn insert_node(node: &mut BkNode, key: String, hash: u64) {
+        let dist = hamming_distance(node.hash, hash);
+        match node.children.get_mut(&dist) {
+            Some(child) => Self::insert_node(child, key, hash),
+            None => {
+                node.children.insert(
+                    dist,
+                    Box::new(BkNode {
+                        key,
+                        hash,
+                        children: HashMap::new(),
+                    }),
+                );
+            }
+        }
+    }
+
+    /// Return every indexed key whose hash is within `threshold` bits of
+    /// `hash`, along with the distance found.
+    pub fn find_within(&self, hash: u64, threshold: u32) -> Vec<(&str, u32)> {
+        let mut matches = Vec::new();
+        if let Some(root) = &self.root {
+            Self::search_node(root, hash, threshold, &mut matches);
+        }
+        matches
+    }
+
+    fn search_node<'a>(node: &'a BkNode, hash: u64, threshold: u32, matches: &mut Vec<(&'a str, u32)>) {
+        let dist = hamming_distance(node.hash, hash);
+        if dist <= threshold {
+            matches.push((node.key.as_str(), dist));
+        }
+
+        let lo = dist.saturating_sub(threshold);
+        let hi = dist + threshold;
+        for d in lo..=hi {
+            if let Some(child) = node.children.get(&d) {
+                Self::search_node(child, hash, threshold, matches);
+            }
+        }
+    }
+}
+
+/// Union-find root lookup with path compression.
+fn find_root(parents: &mut HashMap<String, String>, key: &str) -> String {
+    let mut root = key.to_string();
+    while parents[&root] != root {
+        root = parents[&root].clone();
+    }
+
+    let mut current = key.to_string();
+    while current != root {
+        let next = parents[&current].clone();
+        parents.insert(current, root.clone());
+        current = next;
+    }
+
+    root
+}
+
+/// Merge the sets containing `a` and `b`.
+fn union(parents: &mut HashMap<String, String>, a: &str, b: &str) {
+    let root_a = find_root(parents, a);
+    let root_b = find_root(parents, b);
+    if root_a != root_b {
+        parents.insert(root_a, root_b);
+    }
+}
+
+/// Group photo data base keys whose hashes lie within `threshold` Hamming
+/// distance of one another into near-duplicate clusters.
+///
+/// This is a single connected-components pass over the "within threshold"
+/// relation rather than one independent BK-tree query per seed photo: two
+/// photos connected only through a third (A close to B, B close to C, A far
+/// from C) must end up in the same cluster, and no key may be reported as a
+/// member of more than one cluster.
+pub fn find_duplicate_clusters(
+    hashes: &HashMap<String, u64>,
+    threshold: u32,
+) -> Vec<Vec<String>> {
+    let mut tree = BkTree::new();
+    for (key, hash) in hashes {
+        tree.insert(key.clone(), *hash);
+    }
+
+    let mut parents: HashMap<String, String> =
+        hashes.keys().map(|key| (key.clone(), key.clone())).collect();
+
+    for (key, hash) in hashes {
+        for (other, _) in tree.find_within(*hash, threshold) {
+            if other != key {
+                union(&mut parents, key, other);
+            }
+        }
+    }
+
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for key in hashes.keys() {
+        let root = find_root(&mut parents, key);
+        groups.entry(root).or_default().push(key.clone());
+    }
+
+    let mut clusters: Vec<Vec<String>> = groups
+        .into_values()
+        .filter(|cluster| cluster.len() > 1)
+        .collect();
+    for cluster in &mut clusters {
+        cluster.sort();
+    }
+    clusters.sort();
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hamming_distance_counts_differing_bits() {
+        assert_eq!(hamming_distance(0b0000, 0b0000), 0);
+        assert_eq!(hamming_distance(0b0000, 0b1111), 4);
+        assert_eq!(hamming_distance(0b1010, 0b0101), 4);
+        assert_eq!(hamming_distance(u64::MAX, 0), 64);
+    }
+
+    #[test]
+    fn bktree_find_within_returns_only_close_matches() {
+        let mut tree = BkTree::new();
+        tree.insert("a".to_string(), 0b0000_0000);
+        tree.insert("b".to_string(), 0b0000_0001);
+        tree.insert("c".to_string(), 0b1111_1111);
+
+        let mut matches: Vec<&str> = tree
+            .find_within(0b0000_0000, 1)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        matches.sort();
+
+        assert_eq!(matches, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn bktree_find_within_empty_tree_returns_nothing() {
+        let tree = BkTree::new();
+        assert!(tree.find_within(0, 10).is_empty());
+    }
+
+    #[test]
+    fn find_duplicate_clusters_groups_close_hashes() {
+        let hashes = HashMap::from([
+            ("a".to_string(), 0b0000_0000),
+            ("b".to_string(), 0b0000_0001),
+            ("c".to_string(), 0b1111_1111),
+        ]);
+
+        let mut clusters = find_duplicate_clusters(&hashes, 1);
+        clusters.sort();
+
+        assert_eq!(clusters, vec![vec!["a".to_string(), "b".to_string()]]);
+    }
+
+    #[test]
+    fn find_duplicate_clusters_merges_transitive_chains() {
+        // a-b and b-c are each within threshold, but a-c is not: all three
+        // must still end up in a single cluster rather than two overlapping
+        // ones sharing "b" (see 2b34df5).
+        let hashes = HashMap::from([
+            ("a".to_string(), 0b0000_0000),
+            ("b".to_string(), 0b0000_0011),
+            ("c".to_string(), 0b0000_1111),
+        ]);
+
+        let clusters = find_duplicate_clusters(&hashes, 2);
+
+        assert_eq!(clusters.len(), 1);
+        let mut members = clusters[0].clone();
+        members.sort();
+        assert_eq!(members, vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+    }
+
+    #[test]
+    fn find_duplicate_clusters_drops_singletons() {
+        let hashes = HashMap::from([
+            ("a".to_string(), 0b0000_0000),
+            ("b".to_string(), 0b1111_1111),
+        ]);
+
+        assert!(find_duplicate_clusters(&hashes, 1).is_empty());
+    }
+}
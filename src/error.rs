@@ -0,0 +1,54 @@
+//! Structured error reporting for `photo_tools`.
+//!
+//! Scanning a large photo library will always turn up a few problem entries -
+//! a permission-denied folder, a file with no extension, a non-UTF-8 file
+//! name - and aborting the whole run on the first one is needlessly harsh.
+//! `PhotoError` classifies what went wrong with a single directory entry, and
+//! a `BadEntry` pairs that classification with the offending path so callers
+//! can collect a report of what was skipped instead of crashing on it.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum PhotoError {
+    /// The underlying OS call failed; carries the raw OS error code.
+    OsError(i32),
+    /// The entry has no extension (or no file stem) to classify it by.
+    BadType,
+    /// The entry's path is not valid UTF-8.
+    NonUtf8Path(PathBuf),
+    /// The entry could not be read (e.g. it vanished between listing and use).
+    Unreadable,
+}
+
+impl fmt::Display for PhotoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhotoError::OsError(code) => write!(f, "OS error (code {})", code),
+            PhotoError::BadType => write!(f, "unrecognised file type"),
+            PhotoError::NonUtf8Path(path) => write!(f, "non-UTF-8 path: {}", path.display()),
+            PhotoError::Unreadable => write!(f, "entry could not be read"),
+        }
+    }
+}
+
+impl std::error::Error for PhotoError {}
+
+impl From<io::Error> for PhotoError {
+    fn from(err: io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(code) => PhotoError::OsError(code),
+            None => PhotoError::Unreadable,
+        }
+    }
+}
+
+/// A directory entry that could not be classified or processed, together
+/// with the reason why.
+#[derive(Debug)]
+pub struct BadEntry {
+    pub path: PathBuf,
+    pub error: PhotoError,
+}
@@ -1,21 +1,99 @@
+pub mod dhash;
+pub mod error;
+
+use chrono::Local;
+use error::{BadEntry, PhotoError};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 use std::collections::HashMap;
 use std::fs::{self, DirBuilder};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use xz2::stream::{Check, Filters, LzmaOptions, Stream};
+use xz2::write::XzEncoder;
+
+/// Camera RAW extensions recognised out of the box when `PhotoDir` is built
+/// with the built-in default set enabled.
+pub const DEFAULT_RAW_EXT: &[&str] = &[
+    "mrw", "arw", "sr2", "orf", "rw2", "raf", "dng", "pef", "crw", "nrw", "nef", "cr2", "cr3",
+];
+
+/// Developed-image extensions recognised out of the box when `PhotoDir` is
+/// built with the built-in default set enabled.
+pub const DEFAULT_IMG_EXT: &[&str] = &["jpg", "jpeg", "heic", "png"];
 
 #[derive(Debug)]
 pub struct Photo {
     pub file_name: String,
-    pub has_raw: bool,
-    pub has_jpg: bool,
+    /// Full path of the RAW file for this photo, if one was found.
+    pub raw_path: Option<PathBuf>,
+    /// Full path of the developed image file for this photo, if one was found.
+    pub img_path: Option<PathBuf>,
+    /// Perceptual (dHash) hash of the photo, computed when scanning with the
+    /// `DUP` filter. `None` otherwise.
+    pub hash: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct PhotoDir {
     pub path: PathBuf,
     pub filter: String,
-    pub raw_ext: String,
-    pub img_ext: String,
+    /// RAW extensions considered when classifying files (e.g. `cr2`, `nef`, `raf`).
+    pub raw_ext: Vec<String>,
+    /// Developed-image extensions considered when classifying files (e.g. `jpg`, `heic`).
+    pub img_ext: Vec<String>,
+    /// When set, `photo_database` descends into sub-directories instead of
+    /// only listing the files directly under `path`.
+    pub recursive: bool,
+    /// Glob patterns (e.g. `*/exports/*`) matched against the full path of a
+    /// file or directory to keep it out of the scan.
+    pub excluded_paths: Vec<String>,
+    /// Glob patterns (e.g. `*.tmp`) matched against a file's name to keep it
+    /// out of the scan, on top of `raw_ext`/`img_ext` membership.
+    pub excluded_exts: Vec<String>,
+}
+
+/// Case-insensitive membership test used to classify a file's extension
+/// against a `PhotoDir`'s configured RAW/IMG extension sets.
+pub fn has_ext(extensions: &[String], extension: &str) -> bool {
+    extensions.iter().any(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Always excluded regardless of user configuration, so the staging folder
+/// created by `delete_photos` is never treated as a source of photos, nor
+/// re-scanned for orphans of its own.
+const IMPLICIT_EXCLUDED_PATHS: &[&str] = &["*/to_delete", "*/to_delete/*"];
+
+/// Whether `candidate` matches any of `patterns`, which are glob patterns
+/// (e.g. `*/exports/*`, `*.tmp`). Unparsable patterns are ignored rather than
+/// failing the whole scan.
+fn matches_any_glob(patterns: &[String], candidate: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        glob::Pattern::new(pattern)
+            .map(|pattern| pattern.matches(candidate))
+            .unwrap_or(false)
+    })
+}
+
+/// Whether `path` should be kept out of the scan, either because it matches
+/// one of `photo_dir.excluded_paths` or because it's the `to_delete/` staging
+/// folder itself.
+fn is_path_excluded(photo_dir: &PhotoDir, path: &Path) -> bool {
+    let path_str = match path.to_str() {
+        Some(path_str) => path_str,
+        None => return false,
+    };
+    matches_any_glob(IMPLICIT_EXCLUDED_PATHS, path_str)
+        || matches_any_glob(&photo_dir.excluded_paths, path_str)
+}
+
+/// Whether a file should be kept out of the scan because its name matches
+/// one of `photo_dir.excluded_exts`.
+fn is_name_excluded(photo_dir: &PhotoDir, file: &Path) -> bool {
+    match file.file_name().and_then(|name| name.to_str()) {
+        Some(name) => matches_any_glob(&photo_dir.excluded_exts, name),
+        None => false,
+    }
 }
 
 /// Checks that a path string is valid, and that the user has RW privileges in it
@@ -57,21 +135,202 @@ pub fn make_path(path: &String, verbose: bool) -> io::Result<PathBuf> {
     Ok(new_path)
 }
 
+/// Walk `photo_dir.path` and return the list of directories to scan.
+///
+/// When `photo_dir.recursive` is `false` this simply returns the root on its
+/// own, matching the historic, single-folder behaviour. When it's `true`,
+/// every sub-directory found below the root (to any depth) is included too,
+/// so a whole shoot tree such as `2024/trip/day1`, `day2` is covered in one
+/// run. A sub-directory matching `photo_dir.excluded_paths` (or the
+/// always-excluded `to_delete/` folder) is skipped and never descended into.
+/// A sub-directory that cannot be listed (e.g. permission denied) is recorded
+/// as a `BadEntry` rather than aborting the whole walk.
+fn collect_dirs(photo_dir: &PhotoDir) -> (Vec<PathBuf>, Vec<BadEntry>) {
+    let root = &photo_dir.path;
+    let mut dirs = vec![root.to_path_buf()];
+    let mut bad_entries = Vec::new();
+
+    if photo_dir.recursive {
+        let mut queue = vec![root.to_path_buf()];
+        while let Some(dir) = queue.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(error) => {
+                    bad_entries.push(BadEntry {
+                        path: dir,
+                        error: error.into(),
+                    });
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                let path = match entry {
+                    Ok(entry) => entry.path(),
+                    Err(error) => {
+                        bad_entries.push(BadEntry {
+                            path: dir.clone(),
+                            error: error.into(),
+                        });
+                        continue;
+                    }
+                };
+                if path.is_dir() && !is_path_excluded(photo_dir, &path) {
+                    dirs.push(path.clone());
+                    queue.push(path);
+                }
+            }
+        }
+    }
+
+    (dirs, bad_entries)
+}
+
+/// Classify a directory entry as a `(file_stem, extension)` pair, or report
+/// why it can't be classified (no/unreadable extension, non-UTF-8 name, ...).
+fn classify_entry(file: &Path) -> Result<(String, &str), PhotoError> {
+    let extension = file
+        .extension()
+        .ok_or(PhotoError::BadType)?
+        .to_str()
+        .ok_or_else(|| PhotoError::NonUtf8Path(file.to_path_buf()))?;
+
+    let filename = file
+        .file_stem()
+        .ok_or(PhotoError::BadType)?
+        .to_str()
+        .ok_or_else(|| PhotoError::NonUtf8Path(file.to_path_buf()))?;
+
+    Ok((filename.to_string(), extension))
+}
+
+/// Build the partial photo data base for the files directly under `dir`.
+///
+/// This is the per-directory unit of work handed out to the thread pool by
+/// `photo_database`: it lists `dir` (non-recursively) and classifies every
+/// RAW/IMG file it finds, keying each entry with its full path so that
+/// orphan matching still pairs RAW/IMG files living in the same folder.
+/// Entries that cannot be classified (bad extension, non-UTF-8 name, ...)
+/// are reported as `BadEntry`s instead of aborting the scan.
+fn scan_dir(dir: &Path, photo_dir: &PhotoDir) -> (HashMap<String, Photo>, Vec<BadEntry>) {
+    let mut photo_db: HashMap<String, Photo> = HashMap::new();
+    let mut bad_entries = Vec::new();
+
+    let dir_list = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            bad_entries.push(BadEntry {
+                path: dir.to_path_buf(),
+                error: error.into(),
+            });
+            return (photo_db, bad_entries);
+        }
+    };
+
+    for entry in dir_list {
+        let file = match entry {
+            Ok(entry) => entry.path(),
+            Err(error) => {
+                bad_entries.push(BadEntry {
+                    path: dir.to_path_buf(),
+                    error: error.into(),
+                });
+                continue;
+            }
+        };
+
+        // Omit folders - recursion is handled by collect_dirs
+        if !file.is_file() {
+            continue;
+        }
+
+        // Exclusions are checked before classification, so excluded files
+        // are never treated as source photos.
+        if is_name_excluded(photo_dir, &file) || is_path_excluded(photo_dir, &file) {
+            continue;
+        }
+
+        let (filename, extension) = match classify_entry(&file) {
+            Ok(classified) => classified,
+            Err(error) => {
+                bad_entries.push(BadEntry { path: file, error });
+                continue;
+            }
+        };
+
+        // Detect if the file is a photo file
+        let is_raw = has_ext(&photo_dir.raw_ext, extension);
+        let is_img = has_ext(&photo_dir.img_ext, extension);
+        if !is_raw && !is_img {
+            continue;
+        }
+
+        // Build the key for the data base: directory + stem, independent of
+        // extension, so that a RAW and an IMG file sharing the same stem
+        // pair up regardless of which concrete extension either one uses.
+        let key = match dir.to_str() {
+            Some(dir) => format!("{}/{}", dir, filename),
+            None => {
+                bad_entries.push(BadEntry {
+                    path: file,
+                    error: PhotoError::NonUtf8Path(dir.to_path_buf()),
+                });
+                continue;
+            }
+        };
+
+        // Only the DUP filter needs the (costly) perceptual hash.
+        let hash = if photo_dir.filter == "DUP" {
+            dhash::dhash(&file).ok()
+        } else {
+            None
+        };
+
+        let entry = photo_db.entry(key).or_insert_with(|| Photo {
+            file_name: filename,
+            raw_path: None,
+            img_path: None,
+            hash: None,
+        });
+
+        if is_raw {
+            entry.raw_path = Some(file.clone());
+        }
+        if is_img {
+            entry.img_path = Some(file.clone());
+        }
+        if hash.is_some() {
+            entry.hash = hash;
+        }
+    }
+
+    (photo_db, bad_entries)
+}
+
 /// Build a photography data base from the files included in a directorycar
 ///
 /// # Details
 /// This function lists all the files included in a directory, and makes a
 /// database of those which correspond to photography files. Photos are detected
 /// as RAW files (using the given RAW extension), or IMG files, i.e. developed images
-/// from the RAW files (using the given IMG extension).
+/// from the RAW files (using the given IMG extension). When `photo_dir.recursive`
+/// is set, sub-directories are descended into as well, and the per-directory
+/// listing is fanned out across `threads` worker threads (0 lets rayon pick a
+/// sensible default), merging every partial result into a single data base.
+///
+/// Unreadable directories, bad extensions and non-UTF-8 file names don't
+/// abort the scan: each problem entry is classified and returned alongside
+/// the data base so the caller can report what was skipped.
 ///
 /// # Arguments
 /// - path: a String containing the path to a directory containing photos. An empty \
 ///   String can be passed to the function to indicate the path './'.
+/// - threads: size of the thread pool used to scan directories in parallel. \
+///   0 lets rayon choose a default based on the available cores.
 /// - verbose: enable extra debug information
 /// # Returns:
-/// - *on success*: a `<https://doc.rust-lang.org/std/path/struct.PathBuf.html>` instance.
-/// - *on failure*: a io::Result indicating the source of the error.
+/// - *on success*: the photo data base plus the list of entries that had to be skipped.
+/// - *on failure*: a `PhotoError` indicating the source of the error.
 /// # Example
 /// ```rust
 /// let mypath = String::from("./");
@@ -80,89 +339,261 @@ pub fn make_path(path: &String, verbose: bool) -> io::Result<PathBuf> {
 /// ```
 pub fn photo_database(
     photo_dir: &PhotoDir,
+    threads: usize,
     verbose: bool,
-) -> Result<HashMap<String, Photo>, io::Error> {
-    // List the files in the directory
-    let dir_list = fs::read_dir(&photo_dir.path)
-        .unwrap()
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<PathBuf>, io::Error>>()?;
-
-    // HashMap for the photo data base.
+) -> Result<(HashMap<String, Photo>, Vec<BadEntry>), PhotoError> {
+    let (dirs, mut bad_entries) = collect_dirs(photo_dir);
+
+    if verbose && photo_dir.recursive {
+        println!("\tScanning {} director(y/ies) recursively.", dirs.len());
+    }
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .map_err(|_| PhotoError::Unreadable)?;
+
+    let partials: Vec<(HashMap<String, Photo>, Vec<BadEntry>)> =
+        pool.install(|| dirs.par_iter().map(|dir| scan_dir(dir, photo_dir)).collect());
+
+    // Merge every per-directory partial result into the final data base.
     let mut photo_db: HashMap<String, Photo> = HashMap::new();
+    for (partial_db, partial_bad) in partials {
+        photo_db.extend(partial_db);
+        bad_entries.extend(partial_bad);
+    }
 
-    // Iterate over the files in the directory
-    for file in dir_list {
-        // Omit folders - non recursive algorithm
-        if file.is_file() {
-            // Extract the file extension
-            let extension = file.extension().unwrap().to_str().unwrap();
-            // Detect if the file is photo file
-            if extension == photo_dir.raw_ext || extension == photo_dir.img_ext {
-                // Extract the file name, no path, no extension.
-                let filename = String::from(file.file_stem().unwrap().to_str().unwrap());
-
-                // Is the current file a RAW or a IMG file?
-                let raw = extension == photo_dir.raw_ext.as_str();
-                let jpg = extension == photo_dir.img_ext.as_str();
-
-                // Now, let's build the key for the data base using the extension
-                // marked by the filter.
-                let mut file_path = file.clone();
-                // Pop the file name from the complete file path
-                file_path.pop();
-                let mut file_path = String::from(file_path.to_str().unwrap());
-                file_path += "/";
-                file_path += filename.as_str();
-                file_path += ".";
-
-                // complete the file name using the filter extension
-                if photo_dir.filter.as_str() == "RAW" {
-                    file_path += photo_dir.raw_ext.as_str();
-                } else {
-                    file_path += photo_dir.img_ext.as_str();
-                }
+    if verbose {
+        println!("\tFound {} photo files in the folder.", photo_db.len());
+        if !bad_entries.is_empty() {
+            println!(
+                "\tSkipped {} entries that could not be classified.",
+                bad_entries.len()
+            );
+        }
+    }
 
-                // Detect whether the photo file was already present in the DB
-                // If so, either the RAW or IMG file was listed previously.
-                let was_in = photo_db.get(&file_path);
-
-                match was_in {
-                    Some(_) => {
-                        // Have we found the pair file?
-                        if (photo_db[&file_path].has_raw && jpg)
-                            || (photo_db[&file_path].has_jpg && raw)
-                        {
-                            photo_db.insert(
-                                file_path.clone(),
-                                Photo {
-                                    file_name: filename,
-                                    has_raw: true,
-                                    has_jpg: true,
-                                },
-                            );
-                        }
-                    }
-                    None => {
-                        photo_db.insert(
-                            file_path.clone(),
-                            Photo {
-                                file_name: filename.clone(),
-                                has_raw: raw,
-                                has_jpg: jpg,
-                            },
-                        );
-                    }
+    Ok((photo_db, bad_entries))
+}
+
+/// Group the photos in `photo_db` into near-duplicate clusters.
+///
+/// Only photos carrying a perceptual hash (i.e. scanned with the `DUP`
+/// filter, see [`photo_database`]) are considered; photos whose hash could
+/// not be computed are silently skipped. Clusters with a single member are
+/// dropped since they have no duplicate to report.
+///
+/// # Arguments
+/// - photo_db: the data base produced by `photo_database`.
+/// - threshold: maximum Hamming distance between two hashes for them to be
+///   considered the same photo (10 by default, see
+///   [`dhash::DEFAULT_DUP_THRESHOLD`]).
+pub fn find_duplicates(photo_db: &HashMap<String, Photo>, threshold: u32) -> Vec<Vec<String>> {
+    let hashes: HashMap<String, u64> = photo_db
+        .iter()
+        .filter_map(|(key, photo)| photo.hash.map(|hash| (key.clone(), hash)))
+        .collect();
+
+    dhash::find_duplicate_clusters(&hashes, threshold)
+}
+
+/// Dictionary window used for the xz archive. Larger than the LZMA2 default
+/// presets use, since photo files compress better with more history to
+/// reference against.
+const ARCHIVE_DICT_SIZE: u32 = 192 * 1024 * 1024;
+
+/// `source`'s path relative to `photo_dir.path`, used as the disambiguator
+/// when staging/archiving orphans so that two files sharing a name in
+/// different sub-directories (e.g. per-shoot-day numbering reset) don't
+/// collide. Falls back to just the file name when `source` isn't actually
+/// under `photo_dir.path`.
+fn relative_source_path(photo_dir: &PhotoDir, source: &Path) -> PathBuf {
+    source
+        .strip_prefix(&photo_dir.path)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| PathBuf::from(source.file_name().unwrap_or_default()))
+}
+
+/// The path of the orphan file for `photo` under `photo_dir`'s filter, if any.
+fn orphan_path<'a>(photo_dir: &PhotoDir, photo: &'a Photo) -> Option<&'a PathBuf> {
+    if photo_dir.filter.as_str() == "RAW" {
+        photo.raw_path.as_ref().filter(|_| photo.img_path.is_none())
+    } else {
+        photo.img_path.as_ref().filter(|_| photo.raw_path.is_none())
+    }
+}
+
+/// The photos in `photo_db` that `photo_dir`'s filter would treat as orphans,
+/// i.e. the ones `delete_photos` would move/delete. This is a pure function
+/// so a dry run (see `dry_run_report`) and the real delete path agree on
+/// exactly the same set of files.
+pub fn find_orphans<'a>(photo_dir: &PhotoDir, photo_db: &'a HashMap<String, Photo>) -> Vec<&'a Photo> {
+    photo_db
+        .values()
+        .filter(|photo| orphan_path(photo_dir, photo).is_some())
+        .collect()
+}
+
+/// Per-extension or per-directory orphan stats: how many files, and their
+/// combined size in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OrphanStats {
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Summary produced by a dry run: every orphan `delete_photos` would act on,
+/// grouped by extension and by the directory it lives in, without touching
+/// the filesystem.
+#[derive(Debug, Default)]
+pub struct DryRunReport {
+    pub by_extension: HashMap<String, OrphanStats>,
+    pub by_directory: HashMap<String, OrphanStats>,
+    pub orphans: Vec<PathBuf>,
+}
+
+/// Determine which files `delete_photos` would move/delete and summarise
+/// them by extension and by subdirectory, without moving or deleting
+/// anything.
+pub fn dry_run_report(photo_dir: &PhotoDir, photo_db: &HashMap<String, Photo>) -> DryRunReport {
+    let mut report = DryRunReport::default();
+
+    for photo in find_orphans(photo_dir, photo_db) {
+        // `photo` came out of find_orphans, so it always has an orphan path.
+        let path = orphan_path(photo_dir, photo).unwrap();
+        let bytes = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_string();
+        let directory = path
+            .parent()
+            .and_then(|dir| dir.to_str())
+            .unwrap_or("")
+            .to_string();
+
+        let by_ext = report.by_extension.entry(extension).or_default();
+        by_ext.count += 1;
+        by_ext.bytes += bytes;
+
+        let by_dir = report.by_directory.entry(directory).or_default();
+        by_dir.count += 1;
+        by_dir.bytes += bytes;
+
+        report.orphans.push(path.clone());
+    }
+
+    report
+}
+
+/// Build an xz encoder configured with `level`'s preset but a larger
+/// dictionary window (see `ARCHIVE_DICT_SIZE`).
+fn build_xz_encoder(file: fs::File, level: u32) -> Result<XzEncoder<fs::File>, PhotoError> {
+    let mut lzma_options =
+        LzmaOptions::new_preset(level).map_err(|_| PhotoError::BadType)?;
+    lzma_options.dict_size(ARCHIVE_DICT_SIZE);
+
+    let mut filters = Filters::new();
+    filters.lzma2(&lzma_options);
+
+    let stream = Stream::new_stream_encoder(&filters, Check::Crc64)
+        .map_err(|_| PhotoError::Unreadable)?;
+
+    Ok(XzEncoder::new_stream(file, stream))
+}
+
+/// Append `source` to `tar` under a name that mirrors its path relative to
+/// `photo_dir.path`, so two orphans sharing a file name in different
+/// sub-directories don't overwrite one another inside the archive. The
+/// original is *not* removed here: it stays in place until the archive has
+/// been confirmed durably complete, see `archive_orphans`.
+fn archive_one(
+    tar: &mut tar::Builder<XzEncoder<fs::File>>,
+    photo_dir: &PhotoDir,
+    source: &Path,
+) -> Result<(), PhotoError> {
+    let name = relative_source_path(photo_dir, source);
+    tar.append_path_with_name(source, &name)?;
+    Ok(())
+}
+
+/// Stream every orphan in `photo_db` into a single `to_delete_<timestamp>.tar.xz`
+/// archive instead of copying it into the `to_delete/` folder, so large RAW
+/// libraries don't double their disk usage while staged for review.
+fn archive_orphans(
+    photo_dir: &PhotoDir,
+    photo_db: &HashMap<String, Photo>,
+    compression_level: u32,
+    verbose: bool,
+) -> Result<Vec<BadEntry>, PhotoError> {
+    let mut bad_entries = Vec::new();
+
+    let archive_name = format!("to_delete_{}.tar.xz", Local::now().format("%Y%m%d_%H%M%S"));
+    let mut archive_path = photo_dir.path.clone();
+    archive_path.push(&archive_name);
+
+    let file = fs::File::create(&archive_path)?;
+    let encoder = build_xz_encoder(file, compression_level)?;
+    let mut tar = tar::Builder::new(encoder);
+
+    // Originals are only removed once the archive below is confirmed
+    // durably complete; until then they remain the sole copy on disk, so a
+    // failure partway through a large run can't take out files that were
+    // already appended to the still-open stream.
+    let mut archived = Vec::new();
+
+    for photo in find_orphans(photo_dir, photo_db) {
+        let source = orphan_path(photo_dir, photo).unwrap();
+        match archive_one(&mut tar, photo_dir, source) {
+            Ok(()) => archived.push(source),
+            Err(error) => bad_entries.push(BadEntry {
+                path: source.clone(),
+                error,
+            }),
+        }
+    }
+
+    let encoder = tar.into_inner()?;
+    encoder.finish()?.sync_all()?;
+
+    if verbose {
+        println!("Discarded files compressed into {}", archive_name);
+    }
+
+    for source in archived {
+        match fs::remove_file(source) {
+            Ok(()) => {
+                if verbose {
+                    println!("\tFile {} archived and removed", source.to_str().unwrap());
                 }
             }
+            Err(error) => bad_entries.push(BadEntry {
+                path: source.clone(),
+                error: error.into(),
+            }),
         }
     }
 
-    if verbose {
-        println!("\tFound {} photo files in the folder.", photo_db.len());
+    Ok(bad_entries)
+}
+
+/// Copy `source` into the `to_delete/` folder, mirroring its path relative
+/// to `photo_dir.path` so that two orphans sharing a name in different
+/// sub-directories (e.g. a camera that resets file numbering per shoot day)
+/// land at distinct destinations instead of one silently overwriting the
+/// other, then remove the original. Reports problems as a `PhotoError`
+/// instead of crashing.
+fn move_to_delete_dir(photo_dir: &PhotoDir, source: &Path, delete_path: &Path) -> Result<(), PhotoError> {
+    let destination = delete_path.join(relative_source_path(photo_dir, source));
+    if let Some(parent) = destination.parent() {
+        DirBuilder::new().recursive(true).create(parent)?;
     }
 
-    Ok(photo_db)
+    fs::copy(source, &destination)?;
+    fs::remove_file(source)?;
+    Ok(())
 }
 
 /// Move or delete the photography files marked
@@ -170,10 +601,14 @@ pub fn photo_database(
 /// # Arguments:
 /// - photo_dir: Struct 'PhotoDir'
 ///   String can be passed to the function to indicate the path './'.
+/// - delete: remove the `to_delete/` staging folder once everything's moved into it.
+/// - archive: stream orphans into a `to_delete_<timestamp>.tar.xz` archive instead of \
+///   copying them into the `to_delete/` folder.
+/// - compression_level: xz compression level (0-9) used when `archive` is set.
 /// - verbose: enable extra debug information
 /// # Returns:
-/// - *on success*: a `std::path::PathBuf' instance.
-/// - *on failure*: a io::Result indicating the source of the error.
+/// - *on success*: the list of entries that could not be moved/deleted.
+/// - *on failure*: a `PhotoError` indicating the source of the error.
 /// # Example
 /// ```rust
 /// let mypath = String::from("./");
@@ -184,9 +619,19 @@ pub fn delete_photos(
     photo_dir: &PhotoDir,
     photo_db: &HashMap<String, Photo>,
     delete: bool,
+    archive: bool,
+    compression_level: u32,
     verbose: bool,
-) -> io::Result<()> {
+) -> Result<Vec<BadEntry>, PhotoError> {
+    if archive {
+        if delete {
+            println!("--delete has no effect with --archive: the archive is already the final output.");
+        }
+        return archive_orphans(photo_dir, photo_db, compression_level, verbose);
+    }
+
     const DELETE_DIR_NAME: &str = "to_delete/";
+    let mut bad_entries = Vec::new();
 
     // Create the directory for the discarded files
     let mut remove_dir = photo_dir.path.clone();
@@ -198,43 +643,37 @@ pub fn delete_photos(
     };
 
     if !delete_file_exists {
-        let _builder = DirBuilder::new()
-            .recursive(false)
-            .create(&remove_dir)
-            .unwrap_or_else(|err| {
-                println!("The directory could not be created");
-                std::process::exit(err.raw_os_error().unwrap());
-            });
+        DirBuilder::new().recursive(false).create(&remove_dir)?;
     }
 
+    // Iterate over the photo DB and detect whether a file should be deleted or not
+    let delete_path = remove_dir
+        .to_str()
+        .ok_or_else(|| PhotoError::NonUtf8Path(remove_dir.clone()))?
+        .to_string();
+
     if verbose && !delete {
         println!(
-            "\tFiles to be deleted by the user are located at: {}/{}",
-            photo_dir.path.as_path().to_str().unwrap(),
-            DELETE_DIR_NAME,
+            "\tFiles to be deleted by the user are located at: {}",
+            delete_path.as_str()
         );
     }
 
-    // Iterate over the photo DB and detect whether a file should be deleted or not
-    let delete_path = String::from(remove_dir.as_os_str().to_str().unwrap());
-
-    for (file, val) in photo_db {
-        if (photo_dir.filter.as_str() == "RAW" && (val.has_raw && !val.has_jpg))
-            || (photo_dir.filter == "IMG" && (val.has_jpg && !val.has_raw))
-        {
-            let mut delete_file = delete_path.clone();
-            delete_file.push_str(val.file_name.as_str());
-            delete_file.push_str(".");
-            if photo_dir.filter.as_str() == "RAW" {
-                delete_file.push_str(photo_dir.raw_ext.as_str());
-            } else {
-                delete_file.push_str(photo_dir.img_ext.as_str());
-            }
-            fs::copy(file.as_str(), delete_file.as_str())?;
-            fs::remove_file(file.as_str())?;
-            if verbose {
-                println!("\tFile {} moved to to_delete folder", file.as_str());
+    for val in find_orphans(photo_dir, photo_db) {
+        let source = orphan_path(photo_dir, val).unwrap();
+        match move_to_delete_dir(photo_dir, source, &remove_dir) {
+            Ok(()) => {
+                if verbose {
+                    println!(
+                        "\tFile {} moved to to_delete folder",
+                        source.to_str().unwrap()
+                    );
+                }
             }
+            Err(error) => bad_entries.push(BadEntry {
+                path: source.clone(),
+                error,
+            }),
         }
     }
 
@@ -249,5 +688,72 @@ pub fn delete_photos(
             delete_path.as_str()
         );
     }
-    Ok(())
+    Ok(bad_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo_dir(excluded_paths: &[&str], excluded_exts: &[&str]) -> PhotoDir {
+        PhotoDir {
+            path: PathBuf::from("/photos"),
+            filter: String::from("RAW"),
+            raw_ext: Vec::new(),
+            img_ext: Vec::new(),
+            recursive: true,
+            excluded_paths: excluded_paths.iter().map(|p| p.to_string()).collect(),
+            excluded_exts: excluded_exts.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_any_glob_matches_one_of_several_patterns() {
+        let patterns = vec!["*/exports/*".to_string(), "*.tmp".to_string()];
+        assert!(matches_any_glob(&patterns, "/photos/exports/img.jpg"));
+        assert!(matches_any_glob(&patterns, "scratch.tmp"));
+        assert!(!matches_any_glob(&patterns, "/photos/2024/img.jpg"));
+    }
+
+    #[test]
+    fn matches_any_glob_ignores_unparsable_patterns() {
+        let patterns = vec!["[".to_string()];
+        assert!(!matches_any_glob(&patterns, "anything"));
+    }
+
+    #[test]
+    fn is_path_excluded_honours_user_patterns() {
+        let photo_dir = photo_dir(&["*/exports/*"], &[]);
+        assert!(is_path_excluded(&photo_dir, Path::new("/photos/exports/day1")));
+        assert!(!is_path_excluded(&photo_dir, Path::new("/photos/day1")));
+    }
+
+    #[test]
+    fn is_path_excluded_always_excludes_to_delete() {
+        let photo_dir = photo_dir(&[], &[]);
+        assert!(is_path_excluded(&photo_dir, Path::new("/photos/to_delete")));
+        assert!(is_path_excluded(
+            &photo_dir,
+            Path::new("/photos/to_delete/IMG_0001.RAF")
+        ));
+        assert!(!is_path_excluded(&photo_dir, Path::new("/photos/day1")));
+    }
+
+    #[test]
+    fn is_name_excluded_matches_against_the_file_name_only() {
+        let photo_dir = photo_dir(&[], &["*.tmp"]);
+        assert!(is_name_excluded(&photo_dir, Path::new("/photos/day1/scratch.tmp")));
+        assert!(!is_name_excluded(&photo_dir, Path::new("/photos/day1/IMG_0001.RAF")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn is_name_excluded_ignores_non_utf8_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let photo_dir = photo_dir(&[], &["*"]);
+        let non_utf8 = OsStr::from_bytes(&[0x66, 0x6f, 0x80, 0x6f]);
+        assert!(!is_name_excluded(&photo_dir, Path::new(non_utf8)));
+    }
 }
@@ -2,9 +2,14 @@
 //!
 //! This tool helps managing a big collection of photographies, having both: developed images and
 //! raw files.
-use argparse::{ArgumentParser, Print, Store, StoreTrue};
+use argparse::{ArgumentParser, List, Print, Store, StoreTrue};
 use chrono::prelude::*;
-use photo_tools::{delete_photos, make_path, photo_database, PhotoDir};
+use photo_tools::dhash::DEFAULT_DUP_THRESHOLD;
+use photo_tools::error::BadEntry;
+use photo_tools::{
+    delete_photos, dry_run_report, find_duplicates, has_ext, make_path, photo_database,
+    DryRunReport, OrphanStats, PhotoDir, DEFAULT_IMG_EXT, DEFAULT_RAW_EXT,
+};
 
 struct Options {
     verbose: bool,
@@ -13,6 +18,36 @@ struct Options {
     raw_ext: String,
     photo_del: bool,
     filter: String,
+    recursive: bool,
+    threads: usize,
+    dup_threshold: u32,
+    use_default_exts: bool,
+    archive: bool,
+    compression_level: u32,
+    exclude_paths: Vec<String>,
+    exclude_exts: Vec<String>,
+    dry_run: bool,
+}
+
+/// Parse a comma-separated extension list into a de-duplicated `Vec<String>`,
+/// optionally widened with the tool's built-in default extension set.
+fn parse_extensions(raw: &str, defaults: Option<&[&str]>) -> Vec<String> {
+    let mut extensions: Vec<String> = Vec::new();
+    for ext in raw.split(',').map(str::trim).filter(|ext| !ext.is_empty()) {
+        if !has_ext(&extensions, ext) {
+            extensions.push(ext.to_string());
+        }
+    }
+
+    if let Some(defaults) = defaults {
+        for ext in defaults {
+            if !has_ext(&extensions, ext) {
+                extensions.push(ext.to_string());
+            }
+        }
+    }
+
+    extensions
 }
 
 fn main() {
@@ -24,6 +59,15 @@ fn main() {
         raw_ext: String::from("RAF"),
         img_ext: String::from("JPG"),
         filter: String::new(),
+        recursive: false,
+        threads: 0,
+        dup_threshold: DEFAULT_DUP_THRESHOLD,
+        use_default_exts: false,
+        archive: false,
+        compression_level: 6,
+        exclude_paths: Vec::new(),
+        exclude_exts: Vec::new(),
+        dry_run: false,
     };
 
     // Argument parsing environment
@@ -36,23 +80,32 @@ fn main() {
         // equivalent in the same directory, viceversa when using RAF as filter.
         ap.refer(&mut options.filter)
             .required()
-            .add_argument("FILTER", Store, "IMG or RAW");
+            .add_argument("FILTER", Store, "IMG, RAW or DUP");
         ap.add_option(
             &["-V", "--version"],
             Print(env!("CARGO_PKG_VERSION").to_string()),
             "Show version",
         );
-        // Option to set the extension of the camera raw files. Fujifilm uses RAF (default value).
+        // Option to set the extension(s) of the camera raw files. Fujifilm uses RAF (default
+        // value); a comma-separated list can be used to scan mixed libraries (e.g. "cr2,nef,raf").
         ap.refer(&mut options.raw_ext).add_option(
             &["-r", "--rawext"],
             Store,
-            "Extension of the raw files (RAF by default)",
+            "Comma-separated extensions of the raw files (RAF by default)",
         );
-        // Option to set the extension of the camera image files. Fujifilm uses JPG (default value).
+        // Option to set the extension(s) of the camera image files. Fujifilm uses JPG (default
+        // value); a comma-separated list can be used to scan mixed libraries (e.g. "jpg,heic").
         ap.refer(&mut options.img_ext).add_option(
             &["-j", "--photoext"],
             Store,
-            "Extension of the image files (JPG by default)",
+            "Comma-separated extensions of the image files (JPG by default)",
+        );
+        // Option to widen the configured extensions with the tool's built-in default set, which
+        // covers the common camera RAW formats plus the usual developed-image formats.
+        ap.refer(&mut options.use_default_exts).add_option(
+            &["-D", "--default-exts"],
+            StoreTrue,
+            "Also recognise the tool's built-in default RAW/image extensions",
         );
         // Path of the directory containing the photography files.
         ap.refer(&mut options.photo_dir)
@@ -69,6 +122,58 @@ fn main() {
             StoreTrue,
             "Enable verbose mode",
         );
+        // Option to descend into sub-directories instead of only scanning the top folder.
+        ap.refer(&mut options.recursive).add_option(
+            &["-R", "--recursive"],
+            StoreTrue,
+            "Recursively scan sub-directories of the photo folder",
+        );
+        // Size of the thread pool used to scan directories in parallel.
+        ap.refer(&mut options.threads).add_option(
+            &["-T", "--threads"],
+            Store,
+            "Number of threads used to scan directories (0: let rayon pick a default)",
+        );
+        // Maximum Hamming distance for the DUP filter to consider two photos duplicates.
+        ap.refer(&mut options.dup_threshold).add_option(
+            &["--dup-threshold"],
+            Store,
+            "Max. Hamming distance between two photo hashes to flag them as duplicates (DUP filter)",
+        );
+        // Option to stream discarded files into a single xz archive instead of copying them
+        // into the to_delete/ folder, saving disk space on large RAW libraries.
+        ap.refer(&mut options.archive).add_option(
+            &["--archive"],
+            StoreTrue,
+            "Compress discarded photos into a to_delete_<timestamp>.tar.xz archive",
+        );
+        // xz compression level used when --archive is set.
+        ap.refer(&mut options.compression_level).add_option(
+            &["--compression-level"],
+            Store,
+            "xz compression level used with --archive, 0-9 (6 by default)",
+        );
+        // Glob patterns matched against the full path, to keep whole directories
+        // (e.g. edit/export folders) out of the scan. Repeatable.
+        ap.refer(&mut options.exclude_paths).add_option(
+            &["--exclude-path"],
+            List,
+            "Glob pattern of paths to exclude from the scan, e.g. */exports/* (repeatable)",
+        );
+        // Glob patterns matched against a file's name, to keep extra file types
+        // out of the scan on top of raw_ext/img_ext. Repeatable.
+        ap.refer(&mut options.exclude_exts).add_option(
+            &["--exclude-ext"],
+            List,
+            "Glob pattern of file names to exclude from the scan, e.g. *.tmp (repeatable)",
+        );
+        // Option to preview what delete_photos would do, grouped by extension and
+        // subdirectory, without touching the filesystem.
+        ap.refer(&mut options.dry_run).add_option(
+            &["--dry-run"],
+            StoreTrue,
+            "Report the orphans that would be moved/deleted without touching the filesystem",
+        );
 
         ap.parse_args_or_exit();
     }
@@ -92,17 +197,114 @@ fn main() {
         std::process::exit(err.raw_os_error().unwrap());
     });
 
+    let default_raw_ext = options.use_default_exts.then_some(DEFAULT_RAW_EXT);
+    let default_img_ext = options.use_default_exts.then_some(DEFAULT_IMG_EXT);
+
     let photo = PhotoDir {
         path: photo_dir,
         filter: options.filter,
-        raw_ext: options.raw_ext,
-        img_ext: options.img_ext,
+        raw_ext: parse_extensions(&options.raw_ext, default_raw_ext),
+        img_ext: parse_extensions(&options.img_ext, default_img_ext),
+        recursive: options.recursive,
+        excluded_paths: options.exclude_paths,
+        excluded_exts: options.exclude_exts,
+    };
+
+    let (photo_db, bad_entries) = match photo_database(&photo, options.threads, options.verbose) {
+        Ok(result) => result,
+        Err(error) => {
+            println!("Error: {}", error);
+            std::process::exit(1);
+        }
     };
+    print_bad_entries(&bad_entries);
 
-    let photo_db = photo_database(&photo, options.verbose).unwrap();
+    if photo.filter.as_str() == "DUP" {
+        let clusters = find_duplicates(&photo_db, options.dup_threshold);
+        if clusters.is_empty() {
+            println!("No near-duplicate photos found.");
+        } else {
+            println!("Found {} near-duplicate cluster(s):", clusters.len());
+            for cluster in &clusters {
+                println!("\t{}", cluster.join(", "));
+            }
+            println!(
+                "\tReview the clusters above and move the ones you don't want into {}.",
+                photo.path.join("to_delete/").display()
+            );
+        }
+        return;
+    }
 
-    match delete_photos(&photo, &photo_db, options.photo_del, options.verbose) {
-        Ok(_) => println!("All done!"),
-        Err(error) => println!("{:?}", error),
+    if options.dry_run {
+        print_dry_run_report(&dry_run_report(&photo, &photo_db));
+        return;
+    }
+
+    match delete_photos(
+        &photo,
+        &photo_db,
+        options.photo_del,
+        options.archive,
+        options.compression_level,
+        options.verbose,
+    ) {
+        Ok(bad_entries) => {
+            print_bad_entries(&bad_entries);
+            println!("All done!")
+        }
+        Err(error) => println!("Error: {}", error),
     };
 }
+
+/// Print a short summary of the directory entries that had to be skipped,
+/// if any.
+fn print_bad_entries(bad_entries: &[BadEntry]) {
+    if bad_entries.is_empty() {
+        return;
+    }
+    println!("Could not process {} entries:", bad_entries.len());
+    for entry in bad_entries {
+        println!("\t{}: {}", entry.path.display(), entry.error);
+    }
+}
+
+/// Print the grouped summary produced by a `--dry-run`.
+fn print_dry_run_report(report: &DryRunReport) {
+    if report.orphans.is_empty() {
+        println!("Dry run: no orphan files found.");
+        return;
+    }
+
+    println!(
+        "Dry run: {} orphan file(s) would be moved/deleted.",
+        report.orphans.len()
+    );
+
+    let mut by_extension: Vec<(&String, &OrphanStats)> = report.by_extension.iter().collect();
+    by_extension.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    println!("By extension:");
+    for (extension, stats) in by_extension {
+        println!(
+            "\t.{}: {} file(s), {} bytes",
+            extension, stats.count, stats.bytes
+        );
+    }
+
+    let mut by_directory: Vec<(&String, &OrphanStats)> = report.by_directory.iter().collect();
+    by_directory.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    println!("By directory:");
+    for (directory, stats) in by_directory {
+        println!(
+            "\t{}: {} file(s), {} bytes",
+            directory, stats.count, stats.bytes
+        );
+    }
+
+    println!("Full list:");
+    for orphan in &report.orphans {
+        println!("\t{}", orphan.display());
+    }
+}